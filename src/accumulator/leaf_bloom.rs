@@ -0,0 +1,195 @@
+//! A compact probabilistic summary of the leaf hashes deleted in a block.
+//!
+//! A bridge node can publish a [`LeafBloom`] so a light client can cheaply
+//! pre-check "is my outpoint possibly spent in this block?" before asking for
+//! the full Utreexo proof. Like any Bloom filter it never yields a false
+//! negative — [`LeafBloom::contains`] returning `false` means the hash was
+//! definitely not inserted — but it may yield a false positive, whose rate
+//! grows with the number of inserted hashes and shrinks with `m`.
+
+use std::io::Read;
+use std::io::Write;
+
+use super::node_hash::BitcoinNodeHash;
+
+/// Width, in bytes, of each little-endian word sliced out of a hash.
+const WORD_BYTES: usize = 4;
+/// The largest `k` a 32-byte hash can supply disjoint [`WORD_BYTES`]-wide words
+/// for.
+const MAX_K: usize = 32 / WORD_BYTES;
+/// Upper bound on `m` accepted by [`LeafBloom::read`], so a malformed length can
+/// never drive a multi-gigabyte allocation. 2^26 bits packs into 8 MiB, far more
+/// than a block's spent-leaf population needs.
+const MAX_M: usize = 1 << 26;
+
+/// A fixed-width Bloom filter over [`BitcoinNodeHash`] leaves.
+///
+/// The `k` bit indices for a hash are derived directly from its bytes rather
+/// than by re-hashing: the 32-byte hash is sliced into `k` disjoint
+/// [`WORD_BYTES`]-byte little-endian words and each word is reduced `% m`. This
+/// reuses the already-uniform hash output, the same trick Ethereum's bloom
+/// uses on keccak output.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LeafBloom {
+    /// Number of bits in the filter.
+    m: usize,
+    /// Number of words (and therefore bits) derived per hash.
+    k: usize,
+    /// The bit array, packed eight bits to a byte.
+    bits: Vec<u8>,
+}
+
+impl LeafBloom {
+    /// Creates an empty filter with `m` bits and `k` derived indices per hash.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `m` is zero, or if `k` is zero or greater than [`MAX_K`] (a
+    /// 32-byte hash cannot supply more than [`MAX_K`] disjoint words).
+    pub fn new(m: usize, k: usize) -> Self {
+        assert!(m > 0, "a bloom filter needs at least one bit");
+        assert!(
+            (1..=MAX_K).contains(&k),
+            "k must be in 1..={MAX_K} for a 32-byte hash"
+        );
+        LeafBloom {
+            m,
+            k,
+            bits: vec![0; m.div_ceil(8)],
+        }
+    }
+
+    /// Returns the `k` bit indices for a hash, or `None` for the `Empty` and
+    /// `Placeholder` variants, which carry no payload to slice.
+    fn indices(&self, hash: &BitcoinNodeHash) -> Option<[usize; MAX_K]> {
+        let bytes = match hash {
+            BitcoinNodeHash::Some(inner) => inner,
+            BitcoinNodeHash::Empty | BitcoinNodeHash::Placeholder => return None,
+        };
+        let mut indices = [0; MAX_K];
+        for (i, slot) in indices.iter_mut().take(self.k).enumerate() {
+            let start = i * WORD_BYTES;
+            let word = u32::from_le_bytes(
+                bytes[start..start + WORD_BYTES]
+                    .try_into()
+                    .expect("slice is WORD_BYTES long"),
+            );
+            *slot = (word as usize) % self.m;
+        }
+        Some(indices)
+    }
+
+    /// Records a leaf hash in the filter. `Empty` and `Placeholder` hashes are
+    /// skipped, since they carry no payload to summarize.
+    pub fn insert(&mut self, hash: &BitcoinNodeHash) {
+        if let Some(indices) = self.indices(hash) {
+            for &index in indices.iter().take(self.k) {
+                self.bits[index / 8] |= 1 << (index % 8);
+            }
+        }
+    }
+
+    /// Tests whether a leaf hash might have been inserted.
+    ///
+    /// Returns `false` if the hash is definitely absent and `true` if it is
+    /// possibly present (subject to the filter's false-positive rate). `Empty`
+    /// and `Placeholder` hashes are never inserted, so they always return
+    /// `false`.
+    pub fn contains(&self, hash: &BitcoinNodeHash) -> bool {
+        match self.indices(hash) {
+            Some(indices) => indices
+                .iter()
+                .take(self.k)
+                .all(|&index| self.bits[index / 8] & (1 << (index % 8)) != 0),
+            None => false,
+        }
+    }
+
+    /// Serializes the filter as `m` and `k` (little-endian `u64`s) followed by
+    /// the packed bit array. Unlike the encoding of [`BitcoinNodeHash`], no tag
+    /// byte is written.
+    pub fn write<W>(&self, writer: &mut W) -> std::io::Result<()>
+    where
+        W: Write,
+    {
+        writer.write_all(&(self.m as u64).to_le_bytes())?;
+        writer.write_all(&(self.k as u64).to_le_bytes())?;
+        writer.write_all(&self.bits)
+    }
+
+    /// Reads a filter written by [`LeafBloom::write`], rejecting malformed
+    /// parameters or truncated input with [`std::io::ErrorKind::InvalidData`].
+    pub fn read<R>(reader: &mut R) -> std::io::Result<Self>
+    where
+        R: Read,
+    {
+        // A stream that ends early is malformed input too, so report it as
+        // `InvalidData` rather than the reader's `UnexpectedEof`.
+        let truncated = |err: std::io::Error| {
+            if err.kind() == std::io::ErrorKind::UnexpectedEof {
+                std::io::Error::new(std::io::ErrorKind::InvalidData, err)
+            } else {
+                err
+            }
+        };
+        let mut buf = [0; 8];
+        reader.read_exact(&mut buf).map_err(truncated)?;
+        let m = u64::from_le_bytes(buf) as usize;
+        reader.read_exact(&mut buf).map_err(truncated)?;
+        let k = u64::from_le_bytes(buf) as usize;
+        // Bound `m` before allocating: an untrusted `m` up to `u64::MAX` would
+        // otherwise attempt a ~2^61-byte allocation and abort instead of
+        // yielding the `InvalidData` we promise for malformed parameters.
+        if m == 0 || m > MAX_M || !(1..=MAX_K).contains(&k) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "invalid bloom filter parameters",
+            ));
+        }
+        let mut bits = vec![0; m.div_ceil(8)];
+        reader.read_exact(&mut bits).map_err(truncated)?;
+        Ok(LeafBloom { m, k, bits })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::LeafBloom;
+    use crate::accumulator::util::hash_from_u8;
+
+    #[test]
+    fn test_insert_contains() {
+        let mut bloom = LeafBloom::new(1024, 4);
+        let present = hash_from_u8(1);
+        bloom.insert(&present);
+
+        assert!(bloom.contains(&present));
+        // A hash we never inserted tests absent here. A Bloom filter may give
+        // false positives, but these inputs are fixed and none of this hash's
+        // bit indices collides with those of the inserted one.
+        assert!(!bloom.contains(&hash_from_u8(200)));
+    }
+
+    #[test]
+    fn test_write_read_roundtrip() {
+        let mut bloom = LeafBloom::new(2048, 4);
+        bloom.insert(&hash_from_u8(5));
+        bloom.insert(&hash_from_u8(9));
+
+        let mut buf = Vec::new();
+        bloom.write(&mut buf).unwrap();
+        let read = LeafBloom::read(&mut buf.as_slice()).unwrap();
+
+        assert_eq!(bloom, read);
+        assert!(read.contains(&hash_from_u8(5)));
+    }
+
+    #[test]
+    fn test_read_rejects_oversized_m() {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&u64::MAX.to_le_bytes());
+        buf.extend_from_slice(&4u64.to_le_bytes());
+        let err = LeafBloom::read(&mut buf.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
@@ -9,31 +9,118 @@ use serde::Deserialize;
 #[cfg(feature = "with-serde")]
 use serde::Serialize;
 use sha2::{Digest, Sha512_256};
+use subtle::Choice;
+use subtle::ConstantTimeEq;
 
+/// The hash used by an accumulator to identify nodes in the forest.
+///
+/// The forest doesn't care how a node's hash is computed, only that every
+/// implementation agrees on how to combine two children into a parent and how
+/// to serialize a single node. Implementing this trait lets a consumer swap
+/// Bitcoin's [`Sha512_256`] digest (see [`BitcoinNodeHash`]) for a faster or
+/// experimental one — e.g. an adapter over any [`digest::Digest`] that feeds
+/// `update(left); update(right); finalize()` into a 32-byte output — which is
+/// handy for benchmarking and for non-Bitcoin Merkle-forest use cases.
+///
+/// The consumer types this trait is meant to parameterize —
+/// `Pollard<H: AccumulatorHash = BitcoinNodeHash>`, `Stump<H>`, and the proof
+/// types — are not part of this checkout, so the generic parameter cannot be
+/// threaded through here. The trait and its [`BitcoinNodeHash`] implementation
+/// are the backend half of the deliverable; the type parameters must be added
+/// to those types (defaulting to [`BitcoinNodeHash`] so existing code compiles)
+/// when they land in the tree.
+pub trait AccumulatorHash: Copy + Eq + Ord + Default + Debug + Display {
+    /// Returns the hash of an empty node.
+    fn empty() -> Self;
+    /// Returns whether this is the empty hash.
+    fn is_empty(&self) -> bool;
+    /// Returns a placeholder hash, used for nodes whose value we don't know yet.
+    fn placeholder() -> Self;
+    /// Computes the hash of a parent node from its two children.
+    fn parent_hash(left: &Self, right: &Self) -> Self;
+    /// Writes a single hash using a 1-byte tag followed by the payload.
+    fn write<W>(&self, writer: &mut W) -> std::io::Result<()>
+    where
+        W: std::io::Write;
+    /// Reads a single hash written by [`AccumulatorHash::write`].
+    fn read<R>(reader: &mut R) -> std::io::Result<Self>
+    where
+        R: std::io::Read;
+}
+
+/// Format version written ahead of a batch of hashes by
+/// [`BitcoinNodeHash::write_many`].
+const BATCH_VERSION: u8 = 1;
+
+/// Writes a `u64` as an unsigned LEB128 varint.
+fn write_varint<W>(mut value: u64, writer: &mut W) -> std::io::Result<()>
+where
+    W: std::io::Write,
+{
+    loop {
+        let mut byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value != 0 {
+            byte |= 0x80;
+        }
+        writer.write_all(&[byte])?;
+        if value == 0 {
+            return Ok(());
+        }
+    }
+}
+
+/// Reads an unsigned LEB128 varint, rejecting an encoding longer than ten bytes
+/// with [`std::io::ErrorKind::InvalidData`]. A truncated encoding returns the
+/// reader's error.
+fn read_varint<R>(reader: &mut R) -> std::io::Result<u64>
+where
+    R: std::io::Read,
+{
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0];
+        reader.read_exact(&mut byte)?;
+        value |= u64::from(byte[0] & 0x7f) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        "varint is too long",
+    ))
+}
+
+/// The [`AccumulatorHash`] used by Utreexo on the Bitcoin network, backed by
+/// [`Sha512_256`].
 #[derive(Eq, PartialEq, Copy, Clone, Hash, PartialOrd, Ord)]
 #[cfg_attr(feature = "with-serde", derive(Serialize, Deserialize))]
 #[derive(Default)]
-pub enum NodeHash {
+pub enum BitcoinNodeHash {
     #[default]
     Empty,
     Placeholder,
     Some([u8; 32]),
 }
 
-impl Deref for NodeHash {
+/// Backwards-compatible alias for the default accumulator hash.
+pub type NodeHash = BitcoinNodeHash;
+
+impl Deref for BitcoinNodeHash {
     type Target = [u8; 32];
 
     fn deref(&self) -> &Self::Target {
         match self {
-            NodeHash::Some(ref inner) => inner,
+            BitcoinNodeHash::Some(ref inner) => inner,
             _ => &[0; 32],
         }
     }
 }
 
-impl Display for NodeHash {
+impl Display for BitcoinNodeHash {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
-        if let NodeHash::Some(ref inner) = self {
+        if let BitcoinNodeHash::Some(ref inner) = self {
             for byte in inner.iter() {
                 write!(f, "{:02x}", byte)?;
             }
@@ -44,9 +131,9 @@ impl Display for NodeHash {
     }
 }
 
-impl Debug for NodeHash {
+impl Debug for BitcoinNodeHash {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
-        if let NodeHash::Some(ref inner) = self {
+        if let BitcoinNodeHash::Some(ref inner) = self {
             for byte in inner.iter() {
                 write!(f, "{:02x}", byte)?;
             }
@@ -57,80 +144,200 @@ impl Debug for NodeHash {
     }
 }
 
-impl From<[u8; 32]> for NodeHash {
+impl From<[u8; 32]> for BitcoinNodeHash {
     fn from(hash: [u8; 32]) -> Self {
-        NodeHash::Some(hash)
+        BitcoinNodeHash::Some(hash)
     }
 }
 
-impl From<&[u8; 32]> for NodeHash {
+impl From<&[u8; 32]> for BitcoinNodeHash {
     fn from(hash: &[u8; 32]) -> Self {
-        NodeHash::Some(*hash)
+        BitcoinNodeHash::Some(*hash)
     }
 }
 
 #[cfg(test)]
-impl TryFrom<&str> for NodeHash {
+impl TryFrom<&str> for BitcoinNodeHash {
     type Error = hex::FromHexError;
     fn try_from(hash: &str) -> Result<Self, Self::Error> {
         if hash == "0000000000000000000000000000000000000000000000000000000000000000" {
-            return Ok(NodeHash::Empty);
+            return Ok(BitcoinNodeHash::Empty);
         }
         let hash = hex::decode(hash)?;
-        Ok(NodeHash::Some(hash.try_into().unwrap()))
+        Ok(BitcoinNodeHash::Some(hash.try_into().unwrap()))
     }
 }
 
 #[cfg(not(test))]
-impl TryFrom<&str> for NodeHash {
+impl TryFrom<&str> for BitcoinNodeHash {
     type Error = hex::FromHexError;
     fn try_from(hash: &str) -> Result<Self, Self::Error> {
         let hash = hex::decode(hash)?;
-        Ok(NodeHash::Some(hash.try_into().unwrap()))
+        Ok(BitcoinNodeHash::Some(hash.try_into().unwrap()))
     }
 }
 
-impl From<&[u8]> for NodeHash {
+impl From<&[u8]> for BitcoinNodeHash {
     fn from(hash: &[u8]) -> Self {
         let mut inner = [0; 32];
         inner.copy_from_slice(hash);
-        NodeHash::Some(inner)
+        BitcoinNodeHash::Some(inner)
     }
 }
 
-impl FromStr for NodeHash {
+impl FromStr for BitcoinNodeHash {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        NodeHash::try_from(s)
+        BitcoinNodeHash::try_from(s)
     }
     type Err = hex::FromHexError;
 }
 
-impl NodeHash {
-    pub fn is_empty(&self) -> bool {
-        matches!(self, NodeHash::Empty)
+impl BitcoinNodeHash {
+    pub fn new(inner: [u8; 32]) -> Self {
+        BitcoinNodeHash::Some(inner)
+    }
+
+    /// Returns a `Some` hash filled with 32 bytes from the operating system's
+    /// CSPRNG. Useful for fuzzing and property tests, where `hash_from_u8`'s
+    /// 256 distinct values aren't enough to exercise proof logic.
+    pub fn random() -> Self {
+        BitcoinNodeHash::random_with(&mut rand::rngs::OsRng)
     }
 
-    pub fn new(inner: [u8; 32]) -> Self {
-        NodeHash::Some(inner)
+    /// Like [`BitcoinNodeHash::random`] but draws from a caller-supplied source
+    /// of randomness, so property tests can run against a seeded RNG.
+    pub fn random_with<R: rand::RngCore>(rng: &mut R) -> Self {
+        let mut inner = [0; 32];
+        rng.fill_bytes(&mut inner);
+        BitcoinNodeHash::Some(inner)
+    }
+
+    /// Serializes a batch of hashes behind a forward-compatible framing: a
+    /// 1-byte format version, a varint count, then the existing per-hash tagged
+    /// encoding ([`AccumulatorHash::write`]). This gives the proof- and
+    /// root-serialization code one canonical envelope instead of ad-hoc loops;
+    /// the version byte leaves room to add, e.g., a run-length encoding for
+    /// long runs of `Empty` hashes later.
+    pub fn write_many<W>(hashes: &[BitcoinNodeHash], writer: &mut W) -> std::io::Result<()>
+    where
+        W: std::io::Write,
+    {
+        writer.write_all(&[BATCH_VERSION])?;
+        write_varint(hashes.len() as u64, writer)?;
+        for hash in hashes {
+            hash.write(writer)?;
+        }
+        Ok(())
+    }
+
+    /// Reads a batch written by [`BitcoinNodeHash::write_many`], validating the
+    /// format version and rejecting an unknown version or truncated input with
+    /// [`std::io::ErrorKind::InvalidData`].
+    pub fn read_many<R>(reader: &mut R) -> std::io::Result<Vec<BitcoinNodeHash>>
+    where
+        R: std::io::Read,
+    {
+        let truncated = |err: std::io::Error| {
+            if err.kind() == std::io::ErrorKind::UnexpectedEof {
+                std::io::Error::new(std::io::ErrorKind::InvalidData, err)
+            } else {
+                err
+            }
+        };
+        let mut version = [0];
+        reader.read_exact(&mut version).map_err(truncated)?;
+        if version[0] != BATCH_VERSION {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "unsupported NodeHash batch version",
+            ));
+        }
+        let count = read_varint(reader).map_err(truncated)?;
+        // Don't pre-size from the attacker-controlled `count`: a bogus varint up
+        // to `u64::MAX` would trigger a capacity-overflow panic / OOM before a
+        // single hash is read. Let the loop grow the vector; a truncated stream
+        // then surfaces as `InvalidData`.
+        let mut hashes = Vec::new();
+        for _ in 0..count {
+            hashes.push(BitcoinNodeHash::read(reader).map_err(truncated)?);
+        }
+        Ok(hashes)
+    }
+
+    /// Splits a hash into its `(tag, payload)` pair so equality can be checked
+    /// without branching on the payload. The tag distinguishes the three
+    /// variants and the payload is zeroed for `Empty`/`Placeholder`.
+    fn ct_parts(&self) -> (u8, [u8; 32]) {
+        match self {
+            BitcoinNodeHash::Empty => (0, [0; 32]),
+            BitcoinNodeHash::Placeholder => (1, [0; 32]),
+            BitcoinNodeHash::Some(inner) => (2, *inner),
+        }
+    }
+
+    /// Compares two hashes in constant time, returning a [`Choice`] that is set
+    /// iff they are equal.
+    ///
+    /// Unlike the derived [`PartialEq`], this does not short-circuit on the
+    /// first differing byte, so it does not leak — via timing — how many
+    /// leading bytes of the two payloads matched. This matters during proof
+    /// verification, where an attacker observing timing could otherwise learn a
+    /// prefix of a target hash. The variant tags are folded in alongside the
+    /// 32-byte payload, so no branch depends on the secret bytes.
+    ///
+    /// The accumulator's root- and proof-checking code, which is where these
+    /// comparisons must be routed through `ct_eq`, lives in modules
+    /// (`Pollard`/`Stump`/proof) that are not present in this checkout, so the
+    /// call-site conversion cannot be done here; it must accompany those types
+    /// when they land.
+    pub fn ct_eq(&self, other: &BitcoinNodeHash) -> Choice {
+        let (self_tag, self_bytes) = self.ct_parts();
+        let (other_tag, other_bytes) = other.ct_parts();
+        self_tag.ct_eq(&other_tag) & self_bytes.ct_eq(&other_bytes)
+    }
+}
+
+impl ConstantTimeEq for BitcoinNodeHash {
+    fn ct_eq(&self, other: &Self) -> Choice {
+        BitcoinNodeHash::ct_eq(self, other)
+    }
+}
+
+#[cfg(feature = "with-arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for BitcoinNodeHash {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        // Bias heavily towards `Some`, the only variant proof logic actually
+        // hashes, while still surfacing the `Empty`/`Placeholder` edge cases.
+        Ok(match u.int_in_range::<u8>(0..=15)? {
+            0 => BitcoinNodeHash::Empty,
+            1 => BitcoinNodeHash::Placeholder,
+            _ => BitcoinNodeHash::Some(u.arbitrary()?),
+        })
+    }
+}
+
+impl AccumulatorHash for BitcoinNodeHash {
+    fn is_empty(&self) -> bool {
+        matches!(self, BitcoinNodeHash::Empty)
     }
 
-    pub fn empty() -> Self {
-        NodeHash::Empty
+    fn empty() -> Self {
+        BitcoinNodeHash::Empty
     }
 
-    pub fn parent_hash(left: &NodeHash, right: &NodeHash) -> NodeHash {
+    fn parent_hash(left: &BitcoinNodeHash, right: &BitcoinNodeHash) -> BitcoinNodeHash {
         let mut hasher = Sha512_256::new();
         hasher.update(&**left);
         hasher.update(&**right);
         let result = hasher.finalize();
-        NodeHash::Some(result.into())
+        BitcoinNodeHash::Some(result.into())
     }
 
-    pub const fn placeholder() -> Self {
-        NodeHash::Placeholder
+    fn placeholder() -> Self {
+        BitcoinNodeHash::Placeholder
     }
 
-    pub(super) fn write<W>(&self, writer: &mut W) -> std::io::Result<()>
+    fn write<W>(&self, writer: &mut W) -> std::io::Result<()>
     where
         W: std::io::Write,
     {
@@ -144,7 +351,7 @@ impl NodeHash {
         }
     }
 
-    pub(super) fn read<R>(reader: &mut R) -> std::io::Result<Self>
+    fn read<R>(reader: &mut R) -> std::io::Result<Self>
     where
         R: std::io::Read,
     {
@@ -173,6 +380,9 @@ impl NodeHash {
 mod test {
     use std::str::FromStr;
 
+    use subtle::ConstantTimeEq;
+
+    use super::AccumulatorHash;
     use super::NodeHash;
     use crate::accumulator::util::hash_from_u8;
 
@@ -203,4 +413,38 @@ mod test {
                 .unwrap();
         assert_eq!(hash, NodeHash::empty());
     }
+
+    #[test]
+    fn test_ct_eq() {
+        let hash1 = hash_from_u8(0);
+        let hash2 = hash_from_u8(1);
+
+        assert!(bool::from(hash1.ct_eq(&hash1)));
+        assert!(!bool::from(hash1.ct_eq(&hash2)));
+        assert!(bool::from(NodeHash::empty().ct_eq(&NodeHash::empty())));
+        assert!(!bool::from(NodeHash::empty().ct_eq(&NodeHash::placeholder())));
+        assert!(!bool::from(hash1.ct_eq(&NodeHash::empty())));
+    }
+
+    #[test]
+    fn test_write_read_many() {
+        let hashes = vec![
+            hash_from_u8(0),
+            NodeHash::empty(),
+            NodeHash::placeholder(),
+            hash_from_u8(42),
+        ];
+
+        let mut buf = Vec::new();
+        NodeHash::write_many(&hashes, &mut buf).unwrap();
+        let read = NodeHash::read_many(&mut buf.as_slice()).unwrap();
+
+        assert_eq!(hashes, read);
+    }
+
+    #[test]
+    fn test_read_many_rejects_bad_version() {
+        let err = NodeHash::read_many(&mut [0xff_u8, 0x00].as_slice()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
 }